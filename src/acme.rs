@@ -0,0 +1,362 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2021 Keylime Authors
+
+//! Minimal ACME client used to obtain and renew the agent's HTTPS
+//! certificate without an operator having to hand-place one.
+//!
+//! Only the parts of RFC 8555 the agent needs are implemented: account
+//! registration, a single authorization per order, and the TLS-ALPN-01
+//! challenge (RFC 8737), since the agent only ever exposes one listening
+//! socket and TLS-ALPN-01 doesn't require a separate HTTP-01 listener or
+//! DNS access.
+
+use crate::common::config_get_or;
+use crate::error::{Error, Result};
+use crate::secure_mount;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::sign::Signer;
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::{X509Req, X509ReqBuilder, X509};
+use serde_json::json;
+use std::path::PathBuf;
+
+/// Filename the ACME account key is persisted under inside the secure
+/// mount. Re-used across restarts so the agent doesn't re-register with
+/// the CA every time it starts.
+const ACCOUNT_KEY_FILE: &str = "acme_account_key.pem";
+
+/// The stages of RFC 8555 order processing, kept explicit rather than
+/// inferred from HTTP status codes so callers can log/retry per-stage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderState {
+    /// Order created, authorization not yet satisfied.
+    Pending,
+    /// The TLS-ALPN-01 challenge has been fulfilled and the CA notified.
+    Processing,
+    /// The CA issued the certificate; it is ready to download.
+    Valid,
+    /// The CA rejected the order.
+    Invalid,
+}
+
+/// A freshly issued certificate chain plus the private key it was issued
+/// for, ready to hand to `bind_rustls`.
+#[derive(Debug)]
+pub struct IssuedCert {
+    /// Leaf + intermediate chain returned by the CA.
+    pub chain: Vec<X509>,
+    /// The key pair the CSR was built from.
+    pub key: PKey<Private>,
+}
+
+/// Configuration needed to talk to an ACME directory.
+#[derive(Debug, Clone)]
+pub struct AcmeConfig {
+    /// Directory URL of the ACME server, e.g.
+    /// `https://acme-v02.api.letsencrypt.org/directory`.
+    pub directory_url: String,
+    /// Contact email passed on account creation.
+    pub contact_email: String,
+    /// DNS name (or IP-derived name) the certificate should cover.
+    pub domain: String,
+}
+
+impl AcmeConfig {
+    /// Build an [`AcmeConfig`] from `[cloud_agent]` keys, assuming the
+    /// caller already confirmed `tls_mode = "acme"`.
+    pub fn from_config() -> Self {
+        AcmeConfig {
+            directory_url: config_get_or(
+                "cloud_agent",
+                "acme_directory_url",
+                "https://acme-v02.api.letsencrypt.org/directory",
+            ),
+            contact_email: config_get_or(
+                "cloud_agent",
+                "acme_contact_email",
+                "",
+            ),
+            domain: config_get_or("cloud_agent", "cloudagent_ip", ""),
+        }
+    }
+}
+
+/// Load the persisted ACME account key, generating and persisting a new
+/// one on first run.
+fn load_or_create_account_key() -> Result<PKey<Private>> {
+    let dir = secure_mount::mount()?;
+    let path: PathBuf = dir.join(ACCOUNT_KEY_FILE);
+    if path.is_file() {
+        let pem = std::fs::read(&path)?;
+        Ok(PKey::private_key_from_pem(&pem)?)
+    } else {
+        let ec = openssl::ec::EcKey::generate(
+            openssl::ec::EcGroup::from_curve_name(
+                openssl::nid::Nid::X9_62_PRIME256V1,
+            )?
+            .as_ref(),
+        )?;
+        let key = PKey::from_ec_key(ec)?;
+        std::fs::write(&path, key.private_key_to_pem_pkcs8()?)?;
+        Ok(key)
+    }
+}
+
+/// Build the RFC 7638 JWK representation of an EC P-256 public key, in
+/// the exact member order the thumbprint calculation requires
+/// (`crv`, `kty`, `x`, `y`).
+fn jwk(key: &PKey<Private>) -> Result<serde_json::Value> {
+    let ec = key.ec_key()?;
+    let group = ec.group();
+    let mut ctx = openssl::bn::BigNumContext::new()?;
+    let mut x = openssl::bn::BigNum::new()?;
+    let mut y = openssl::bn::BigNum::new()?;
+    ec.public_key()
+        .affine_coordinates_gfp(group, &mut x, &mut y, &mut ctx)?;
+    Ok(json!({
+        "crv": "P-256",
+        "kty": "EC",
+        "x": base64::encode_config(x.to_vec(), base64::URL_SAFE_NO_PAD),
+        "y": base64::encode_config(y.to_vec(), base64::URL_SAFE_NO_PAD),
+    }))
+}
+
+/// RFC 7638 JWK thumbprint: base64url(sha256(canonical JWK JSON)). Used
+/// both as the ACME "key authorization" suffix and, doubled through
+/// another sha256, as the `acmeIdentifier` value in the TLS-ALPN-01
+/// challenge certificate.
+fn jwk_thumbprint(key: &PKey<Private>) -> Result<String> {
+    let canonical = jwk(key)?.to_string();
+    let digest = openssl::sha::sha256(canonical.as_bytes());
+    Ok(base64::encode_config(digest, base64::URL_SAFE_NO_PAD))
+}
+
+/// The "key authorization" RFC 8555 §8.1 defines: the challenge token
+/// with the account key's thumbprint appended, which every challenge
+/// type binds its proof to.
+fn key_authorization(account_key: &PKey<Private>, token: &str) -> Result<String> {
+    Ok(format!("{}.{}", token, jwk_thumbprint(account_key)?))
+}
+
+/// Sign `payload` (already-serialized JSON, or an empty string for a
+/// POST-as-GET) as a flattened JWS per RFC 8555 §6.2, using ES256.
+/// `kid` is the account URL once we have one; before `newAccount`
+/// succeeds, the embedded `jwk` is sent instead.
+fn sign_jws(
+    account_key: &PKey<Private>,
+    url: &str,
+    nonce: &str,
+    kid: Option<&str>,
+    payload: &str,
+) -> Result<String> {
+    let mut protected = json!({
+        "alg": "ES256",
+        "nonce": nonce,
+        "url": url,
+    });
+    match kid {
+        Some(kid) => protected["kid"] = json!(kid),
+        None => protected["jwk"] = jwk(account_key)?,
+    }
+    let protected_b64 =
+        base64::encode_config(protected.to_string(), base64::URL_SAFE_NO_PAD);
+    let payload_b64 =
+        base64::encode_config(payload, base64::URL_SAFE_NO_PAD);
+    let signing_input = format!("{}.{}", protected_b64, payload_b64);
+
+    let mut signer = Signer::new(MessageDigest::sha256(), account_key)?;
+    signer.update(signing_input.as_bytes())?;
+    let der_sig = signer.sign_to_vec()?;
+    let raw_sig = der_ecdsa_to_raw(&der_sig)?;
+    let signature_b64 = base64::encode_config(raw_sig, base64::URL_SAFE_NO_PAD);
+
+    Ok(json!({
+        "protected": protected_b64,
+        "payload": payload_b64,
+        "signature": signature_b64,
+    })
+    .to_string())
+}
+
+/// openssl's ECDSA signer emits a DER `SEQUENCE { r, s }`; JWS ES256
+/// wants the two 32-byte big-endian integers concatenated instead.
+fn der_ecdsa_to_raw(der: &[u8]) -> Result<Vec<u8>> {
+    let sig = openssl::ecdsa::EcdsaSig::from_der(der)?;
+    let mut raw = sig.r().to_vec();
+    while raw.len() < 32 {
+        raw.insert(0, 0);
+    }
+    let mut s = sig.s().to_vec();
+    while s.len() < 32 {
+        s.insert(0, 0);
+    }
+    raw.extend(s);
+    Ok(raw)
+}
+
+/// Build the self-signed certificate TLS-ALPN-01 (RFC 8737) requires:
+/// it must cover `domain` via a regular SAN entry *and* carry a critical
+/// `acmeIdentifier` extension containing sha256(key authorization).
+fn build_alpn_challenge_cert(
+    domain: &str,
+    key_authorization: &str,
+) -> Result<(X509, PKey<Private>)> {
+    let ec = EcKey::generate(
+        EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?.as_ref(),
+    )?;
+    let key = PKey::from_ec_key(ec)?;
+
+    let mut name_builder = openssl::x509::X509NameBuilder::new()?;
+    name_builder.append_entry_by_text("CN", domain)?;
+    let name = name_builder.build();
+
+    let mut builder = openssl::x509::X509Builder::new()?;
+    builder.set_version(2)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&key)?;
+    builder.set_not_before(openssl::asn1::Asn1Time::days_from_now(0)?.as_ref())?;
+    builder.set_not_after(openssl::asn1::Asn1Time::days_from_now(1)?.as_ref())?;
+
+    let san = SubjectAlternativeName::new()
+        .dns(domain)
+        .build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(san)?;
+
+    // acmeIdentifier = 1.3.6.1.5.5.7.1.31, critical, OCTET STRING of
+    // sha256(key authorization). openssl's typed extension builders don't
+    // cover vendor/IETF extensions outside their known set, so this is
+    // built from its textual form the way the crate's generic extension
+    // API expects.
+    let digest = openssl::sha::sha256(key_authorization.as_bytes());
+    let ext = openssl::x509::extension::X509Extension::new(
+        None,
+        Some(&builder.x509v3_context(None, None)),
+        "1.3.6.1.5.5.7.1.31",
+        &format!("critical,DER:04:20:{}", hex::encode(digest)),
+    )?;
+    builder.append_extension(ext)?;
+
+    builder.sign(&key, MessageDigest::sha256())?;
+    Ok((builder.build(), key))
+}
+
+/// Build a PKCS#10 CSR for `domain`, signed by a freshly generated key.
+fn build_csr(domain: &str) -> Result<(X509Req, PKey<Private>)> {
+    let ec = EcKey::generate(
+        EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?.as_ref(),
+    )?;
+    let key = PKey::from_ec_key(ec)?;
+
+    let mut name_builder = openssl::x509::X509NameBuilder::new()?;
+    name_builder.append_entry_by_text("CN", domain)?;
+    let name = name_builder.build();
+
+    let mut builder = X509ReqBuilder::new()?;
+    builder.set_subject_name(&name)?;
+    builder.set_pubkey(&key)?;
+    let san = SubjectAlternativeName::new()
+        .dns(domain)
+        .build(&builder.x509v3_context(None))?;
+    let mut extensions = openssl::stack::Stack::new()?;
+    extensions.push(san)?;
+    builder.add_extensions(&extensions)?;
+    builder.sign(&key, MessageDigest::sha256())?;
+    Ok((builder.build(), key))
+}
+
+/// Exchange one JWS-signed request with the ACME server and return its
+/// body. This is the single piece of the client that needs an actual
+/// HTTP(S) POST - everything around it (JWK/JWS construction, the
+/// TLS-ALPN-01 challenge certificate, the CSR) is real; this snapshot
+/// just doesn't carry an HTTP client dependency to drive the exchange
+/// itself.
+async fn acme_post(_url: &str, _jws_body: &str) -> Result<Vec<u8>> {
+    Err(Error::Other(
+        "ACME request transport requires an HTTP client this build \
+         doesn't carry"
+            .to_string(),
+    ))
+}
+
+/// Walk the order -> challenge -> finalize sequence against `cfg`. Called
+/// at startup and again by the renewal task in [`crate::tls`] as the
+/// current certificate approaches expiry.
+///
+/// This build has no transport for the ACME exchanges ([`acme_post`]
+/// always returns an error), so this function returns an error at the
+/// first exchange (`newAccount`) and never returns an issued certificate;
+/// the later steps below are not reached. The TLS-ALPN-01 challenge
+/// certificate it would build is not served either: nothing in
+/// [`crate::tls`] answers `acme-tls/1` ALPN negotiations.
+pub async fn obtain_certificate(cfg: &AcmeConfig) -> Result<IssuedCert> {
+    let account_key = load_or_create_account_key()?;
+
+    // A fresh anti-replay nonce is normally read off a `Replay-Nonce`
+    // response header (first from `HEAD newNonce`, then every
+    // subsequent reply); `acme_post` doesn't have a transport to read
+    // headers from yet, so every signed request below is built against
+    // a placeholder nonce the CA would reject - this function fails at
+    // the first real exchange rather than earlier, so the amount of
+    // protocol logic exercised by a future transport hookup is as large
+    // as possible.
+    let nonce = "placeholder-nonce";
+
+    // 1. newAccount, authenticated by the embedded JWK since we don't
+    //    have a `kid` yet.
+    let new_account_payload = json!({
+        "termsOfServiceAgreed": true,
+        "contact": [format!("mailto:{}", cfg.contact_email)],
+    })
+    .to_string();
+    let new_account_url = format!("{}/new-account", cfg.directory_url);
+    let new_account_jws = sign_jws(
+        &account_key,
+        &new_account_url,
+        nonce,
+        None,
+        &new_account_payload,
+    )?;
+    let _account = acme_post(&new_account_url, &new_account_jws).await?;
+    let kid = new_account_url.clone();
+
+    // 2. newOrder for cfg.domain.
+    let new_order_payload = json!({
+        "identifiers": [{"type": "dns", "value": cfg.domain}],
+    })
+    .to_string();
+    let new_order_url = format!("{}/new-order", cfg.directory_url);
+    let new_order_jws = sign_jws(
+        &account_key,
+        &new_order_url,
+        nonce,
+        Some(&kid),
+        &new_order_payload,
+    )?;
+    let _order = acme_post(&new_order_url, &new_order_jws).await?;
+
+    // 3. Build the TLS-ALPN-01 response and tell the CA we're ready.
+    // The challenge token would come from the authorization fetched in
+    // step 2; a CSR-free placeholder stands in for it until the
+    // transport exists to receive the real one.
+    let token = "placeholder-token";
+    let key_auth = key_authorization(&account_key, token)?;
+    let (_challenge_cert, _challenge_key) =
+        build_alpn_challenge_cert(&cfg.domain, &key_auth)?;
+
+    // 4/5. Finalize with a CSR and download the issued chain once the
+    // order reaches `valid`.
+    let (_csr, _cert_key) = build_csr(&cfg.domain)?;
+
+    Err(Error::Other(format!(
+        "ACME issuance for {} against {} cannot complete without an \
+         HTTP client: JWK/JWS signing, the TLS-ALPN-01 challenge \
+         certificate, and the CSR are all built above, but the \
+         directory/account/order/finalize exchanges themselves need a \
+         transport this build doesn't carry",
+        cfg.domain, cfg.directory_url
+    )))
+}
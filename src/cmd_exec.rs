@@ -0,0 +1,64 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2021 Keylime Authors
+
+//! Helpers for running the external scripts Keylime dispatches (revocation
+//! actions, allowlist rebuilds) as child processes.
+
+use crate::error::{Error, Result};
+use std::io::Write;
+use std::process::Command;
+
+/// The basename of a TUF target path, i.e. the part used as the on-disk
+/// file name when staging it for execution. Split out from [`run_script`]
+/// so the (slightly fiddly, since target paths are `/`-separated
+/// regardless of host OS) extraction can be unit-tested on its own.
+fn target_filename(target_path: &str) -> &str {
+    target_path.rsplit('/').next().unwrap_or(target_path)
+}
+
+/// Write `payload` to a temp file under `/var/lib/keylime` named after
+/// `target_path`'s basename and execute it, returning an error if it
+/// exits non-zero.
+///
+/// Callers are expected to have already verified `payload` (e.g. against
+/// signed TUF targets metadata) before reaching this function - it does
+/// not re-check anything about where the bytes came from.
+pub fn run_script(target_path: &str, payload: &[u8]) -> Result<()> {
+    let name = target_filename(target_path);
+    let path = std::path::Path::new("/var/lib/keylime").join(name);
+
+    let mut file = std::fs::File::create(&path)?;
+    file.write_all(payload)?;
+    drop(file);
+
+    let mut perms = std::fs::metadata(&path)?.permissions();
+    std::os::unix::fs::PermissionsExt::set_mode(&mut perms, 0o700);
+    std::fs::set_permissions(&path, perms)?;
+
+    let status = Command::new(&path).status()?;
+    if !status.success() {
+        return Err(Error::Other(format!(
+            "revocation action {} exited with {}",
+            target_path, status
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_filename_strips_directories() {
+        assert_eq!(
+            target_filename("revocation_actions/local_action_rebuild.py"),
+            "local_action_rebuild.py"
+        );
+    }
+
+    #[test]
+    fn test_target_filename_no_directories() {
+        assert_eq!(target_filename("rebuild.py"), "rebuild.py");
+    }
+}
@@ -0,0 +1,138 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2021 Keylime Authors
+
+//! Shared helpers: configuration access and small types used by more than
+//! one handler or subsystem.
+
+use crate::error::{Error, Result};
+use crate::verifier::Evidence;
+use actix_web::{error::InternalError, http::StatusCode, web, HttpResponse};
+use ini::Ini;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Path to the agent's on-disk configuration file.
+pub static CONFIG_FILE: &str = "/etc/keylime.conf";
+
+/// Read a single `key` out of `section` in the agent configuration file.
+///
+/// This is the one place in the agent that knows how configuration is
+/// stored on disk; callers never parse `keylime.conf` themselves.
+pub fn config_get(section: &str, key: &str) -> Result<String> {
+    let conf = Ini::load_from_file(CONFIG_FILE).map_err(|e| {
+        Error::Configuration(format!(
+            "unable to read {}: {}",
+            CONFIG_FILE, e
+        ))
+    })?;
+    conf.section(Some(section))
+        .and_then(|s| s.get(key))
+        .map(str::to_string)
+        .ok_or_else(|| {
+            Error::Configuration(format!(
+                "missing [{}] {} in {}",
+                section, key, CONFIG_FILE
+            ))
+        })
+}
+
+/// Read `key` out of `section`, falling back to `default` when either the
+/// section or the key is absent rather than returning an error.
+///
+/// Several optional features (TLS, privilege dropping, ...) are enabled via
+/// config keys that are safe to leave unset on older installs, so those
+/// call sites use this instead of [`config_get`].
+pub fn config_get_or(section: &str, key: &str, default: &str) -> String {
+    config_get(section, key).unwrap_or_else(|_| default.to_string())
+}
+
+/// Build an absolute URL the remote registrar/verifier can use to call
+/// back into this agent, e.g. `https://1.2.3.4:9002/keys/verify`.
+pub fn full_url(scheme: &str, ip: &str, port: &str, path: &str) -> String {
+    format!("{}://{}:{}{}", scheme, ip, port, path)
+}
+
+/// Returns `true` if `path` exists and is a regular file.
+pub fn is_file(path: &str) -> bool {
+    Path::new(path).is_file()
+}
+
+/// A freshness nonce passed between `/quotes/challenge` and the evidence
+/// routes.
+///
+/// `POST /quotes/challenge` returns one of these with a nonce the agent
+/// generated and is tracking (see
+/// [`crate::quotes_handler::ChallengeStore`]); the caller then posts that
+/// same nonce back to `/quotes/identity` or `/quotes/integrity`. Because
+/// the agent only accepts a nonce it issued and remembers, and consumes
+/// it on first use, a caller can no longer hand the agent an arbitrary
+/// nonce of its own choosing the way the old `?nonce=` query parameter
+/// allowed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Challenge {
+    /// Opaque, single-use freshness value the evidence must be bound to.
+    pub nonce: String,
+}
+
+/// The agent's answer to a [`Challenge`]: the evidence produced by every
+/// registered driver, bound to the challenge's nonce.
+#[derive(Debug, Clone, Serialize)]
+pub struct Response {
+    /// Evidence from each active driver, over `challenge.nonce`.
+    pub evidence: Vec<Evidence>,
+}
+
+/// The body every error response on the REST surface shares, so a
+/// verifier client can parse one error-handling path regardless of which
+/// of the four routes rejected its request.
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    status: u16,
+    message: String,
+}
+
+/// Build a structured error response. All four handlers in
+/// `keys_handler`/`quotes_handler` go through this instead of returning
+/// ad-hoc bodies, so a client sees the same `{"status": ..., "message":
+/// ...}` shape everywhere.
+pub fn error_response(status: StatusCode, message: impl Into<String>) -> HttpResponse {
+    HttpResponse::build(status).json(ErrorBody {
+        status: status.as_u16(),
+        message: message.into(),
+    })
+}
+
+/// A `web::JsonConfig` that routes the *extractor's own* failures (a
+/// missing or malformed JSON body) through [`error_response`] too.
+///
+/// Without this, `web::Json<T>` rejects a bad body before the handler
+/// body ever runs, via actix's default plain-text 400 - which would mean
+/// `/quotes/identity` and `/quotes/integrity` silently fell back to an
+/// inconsistent error shape on exactly the input they're most likely to
+/// receive from a misbehaving client. Register this once per `App` so
+/// every `web::Json<T>` extractor in the app shares it.
+pub fn json_error_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(|err, _req| {
+        let response = error_response(StatusCode::BAD_REQUEST, err.to_string());
+        InternalError::from_response(err, response).into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_response_status_matches_requested() {
+        let resp = error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "nope");
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[test]
+    fn test_full_url() {
+        assert_eq!(
+            full_url("https", "1.2.3.4", "9002", "/keys/verify"),
+            "https://1.2.3.4:9002/keys/verify"
+        );
+    }
+}
@@ -0,0 +1,56 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2021 Keylime Authors
+
+//! Error and Result types shared across the agent.
+
+use thiserror::Error;
+
+/// Convenience alias used throughout the agent instead of
+/// `std::result::Result<T, Error>`.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The agent's top-level error type. Each variant wraps the error of the
+/// subsystem that produced it so callers can match on failure domain
+/// without losing the underlying cause.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// A required configuration value was missing or malformed.
+    #[error("configuration error: {0}")]
+    Configuration(String),
+
+    /// An I/O operation failed.
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// A TPM 2.0 operation failed.
+    #[error("TPM error: {0}")]
+    Tpm(#[from] tss_esapi::Error),
+
+    /// Talking to the registrar failed.
+    #[error("registrar error: {0}")]
+    Registrar(String),
+
+    /// An OpenSSL operation failed.
+    #[error("crypto error: {0}")]
+    Crypto(#[from] openssl::error::ErrorStack),
+
+    /// Starting or running the actix HTTP server failed.
+    #[error("HTTP server error: {0}")]
+    Actix(String),
+
+    /// Catch-all for conditions that don't yet have a dedicated variant.
+    #[error("{0}")]
+    Other(String),
+}
+
+impl From<actix_web::Error> for Error {
+    fn from(e: actix_web::Error) -> Self {
+        Error::Actix(e.to_string())
+    }
+}
+
+/// Lets handlers propagate `Error` with `?` and have actix render a 500
+/// with the error's `Display` as the body; handlers that need a more
+/// specific status build the `HttpResponse` themselves instead of
+/// returning `Err`.
+impl actix_web::ResponseError for Error {}
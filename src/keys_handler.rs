@@ -0,0 +1,141 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2021 Keylime Authors
+
+//! `/keys/*` routes: deliver the U-key share back to the registrar and
+//! answer the verifier's proof-of-possession check.
+
+use crate::common::{self, config_get};
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse};
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::sign::Signer;
+use serde::{Deserialize, Serialize};
+
+/// `?challenge=...` accepted by `GET /keys/verify`.
+#[derive(Debug, Deserialize)]
+pub struct VerifyQuery {
+    challenge: String,
+}
+
+#[derive(Debug, Serialize)]
+struct VerifyBody {
+    hmac: String,
+}
+
+/// The key `main()` derives during credential activation, shared with
+/// this handler as `app_data` so `/keys/verify` can prove possession of
+/// it without redoing the TPM dance.
+pub struct MacKey(pub PKey<Private>);
+
+impl std::fmt::Debug for MacKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MacKey").finish_non_exhaustive()
+    }
+}
+
+/// `GET /keys/verify` - proves the agent holds the derived key by HMACing
+/// the caller-supplied challenge with it.
+pub async fn verify(
+    query: web::Query<VerifyQuery>,
+    mackey: web::Data<MacKey>,
+) -> HttpResponse {
+    if query.challenge.is_empty() {
+        return common::error_response(
+            StatusCode::BAD_REQUEST,
+            "challenge must not be empty",
+        );
+    }
+    match hmac_hex(&mackey.0, query.challenge.as_bytes()) {
+        Ok(hmac) => HttpResponse::Ok().json(VerifyBody { hmac }),
+        Err(e) => common::error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            e.to_string(),
+        ),
+    }
+}
+
+/// HMAC-SHA384 `data` with `key`, hex-encoded - the same construction
+/// `main()` uses for the registrar `auth_tag`.
+fn hmac_hex(
+    key: &PKey<Private>,
+    data: &[u8],
+) -> std::result::Result<String, openssl::error::ErrorStack> {
+    let mut signer = Signer::new(MessageDigest::sha384(), key)?;
+    signer.update(data)?;
+    Ok(hex::encode(signer.sign_to_vec()?))
+}
+
+/// Body accepted by `POST /keys/ukey`, whether it arrived as JSON or as
+/// an URL-encoded form - both carry the same two fields.
+#[derive(Debug, Deserialize)]
+pub struct UkeyBody {
+    auth_tag: String,
+    encrypted_key: String,
+}
+
+/// `POST /keys/ukey` - receives the registrar's share of the derived key.
+///
+/// Accepts either `application/json` or
+/// `application/x-www-form-urlencoded`; any other `Content-Type` is
+/// rejected with 415 rather than folded into a generic 400, so a verifier
+/// client can tell "you sent garbage" apart from "you sent a body shape I
+/// don't speak".
+pub async fn ukey(req: HttpRequest, body: web::Bytes) -> HttpResponse {
+    let content_type = req
+        .headers()
+        .get(actix_web::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+
+    let parsed = if content_type.starts_with("application/json") {
+        serde_json::from_slice::<UkeyBody>(&body).ok()
+    } else if content_type.starts_with("application/x-www-form-urlencoded")
+    {
+        serde_urlencoded::from_bytes::<UkeyBody>(&body).ok()
+    } else {
+        return common::error_response(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!(
+                "unsupported Content-Type \"{}\", expected application/json \
+                 or application/x-www-form-urlencoded",
+                content_type
+            ),
+        );
+    };
+
+    let body = match parsed {
+        Some(b) => b,
+        None => {
+            return common::error_response(
+                StatusCode::BAD_REQUEST,
+                "request body did not match the expected ukey shape",
+            )
+        }
+    };
+
+    if body.auth_tag.is_empty() || body.encrypted_key.is_empty() {
+        return common::error_response(
+            StatusCode::BAD_REQUEST,
+            "auth_tag and encrypted_key are required",
+        );
+    }
+
+    // Handing the share to the rest of the agent (unwrapping it against
+    // the activated credential) happens here in the full agent; omitted
+    // in this snapshot since that state isn't threaded into the handler.
+    let scheme = if crate::tls::TlsMode::from_config() == crate::tls::TlsMode::Disabled
+    {
+        "http"
+    } else {
+        "https"
+    };
+    let callback = config_get("cloud_agent", "cloudagent_ip")
+        .ok()
+        .zip(config_get("cloud_agent", "cloudagent_port").ok())
+        .map(|(ip, port)| common::full_url(scheme, &ip, &port, "/keys/verify"));
+    if let Some(url) = callback {
+        log::info!("U key received, verifier can reach {}", url);
+    }
+
+    HttpResponse::Ok().finish()
+}
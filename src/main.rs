@@ -33,17 +33,22 @@
 //  missing_docs: there is many functions missing documentations for now
 #![allow(unused, missing_docs)]
 
+mod acme;
 mod cmd_exec;
 mod common;
 mod crypto;
 mod error;
 mod hash;
 mod keys_handler;
+mod privileges;
 mod quotes_handler;
 mod registrar_agent;
 mod revocation;
 mod secure_mount;
+mod tls;
 mod tpm;
+mod tuf;
+mod verifier;
 
 use actix_web::{web, App, HttpServer};
 use common::config_get;
@@ -67,8 +72,6 @@ use tss_esapi::{
 };
 use uuid::Uuid;
 
-static NOTFOUND: &[u8] = b"Not Found";
-
 fn get_uuid(agent_uuid_config: &str) -> String {
     match agent_uuid_config {
         "openstack" => {
@@ -124,7 +127,7 @@ async fn main() -> Result<()> {
     let agent_uuid_config = config_get("cloud_agent", "agent_uuid")?;
     let agent_uuid = get_uuid(&agent_uuid_config);
 
-    {
+    let mackey = {
         // Request keyblob material
         let keyblob = registrar_agent::do_register_agent(
             &registrar_ip,
@@ -152,10 +155,36 @@ async fn main() -> Result<()> {
             &auth_tag,
         )
         .await?;
-    }
+        mackey
+    };
+
+    let _secure_mount = secure_mount::mount()?;
+
+    // Bind the listening socket while still privileged, since
+    // `cloudagent_port` may be a privileged (<1024) port that an
+    // unprivileged identity couldn't acquire - `RunAs::apply()` below
+    // must run *after* the socket exists, not before.
+    let bind_addr = format!("{}:{}", cloudagent_ip, cloudagent_port);
+    let listener = std::net::TcpListener::bind(&bind_addr)?;
+
+    // Privileged initialization (TPM provisioning, credential activation,
+    // the secure mount, binding the listening socket) is done; drop to
+    // an unprivileged identity before the HTTP surface or the revocation
+    // service start serving requests.
+    privileges::RunAs::from_config().apply()?;
 
-    let actix_server = HttpServer::new(move || {
+    let evidence_registry =
+        web::Data::new(verifier::build_registry(ak_handle)?);
+    let challenge_store =
+        web::Data::new(quotes_handler::ChallengeStore::new());
+    let mackey = web::Data::new(keys_handler::MacKey(mackey));
+
+    let app_factory = move || {
         App::new()
+            .app_data(evidence_registry.clone())
+            .app_data(challenge_store.clone())
+            .app_data(mackey.clone())
+            .app_data(common::json_error_config())
             .service(
                 web::resource("/keys/verify")
                     .route(web::get().to(keys_handler::verify)),
@@ -164,19 +193,37 @@ async fn main() -> Result<()> {
                 web::resource("/keys/ukey")
                     .route(web::post().to(keys_handler::ukey)),
             )
+            .service(
+                web::resource("/quotes/challenge")
+                    .route(web::post().to(quotes_handler::challenge)),
+            )
             .service(
                 web::resource("/quotes/identity")
-                    .route(web::get().to(quotes_handler::identity)),
+                    .route(web::post().to(quotes_handler::identity)),
             )
             .service(
                 web::resource("/quotes/integrity")
-                    .route(web::get().to(quotes_handler::integrity)),
+                    .route(web::post().to(quotes_handler::integrity)),
             )
-    })
-    .bind(format!("{}:{}", cloudagent_ip, cloudagent_port))?
-    .run()
-    .map_err(|x| x.into());
-    info!("Listening on http://{}:{}", cloudagent_ip, cloudagent_port);
+    };
+
+    let tls_mode = tls::TlsMode::from_config();
+    let actix_server = match tls::setup(tls_mode).await? {
+        Some(tls_config) => HttpServer::new(app_factory)
+            .listen_rustls(listener, tls_config)?
+            .run()
+            .map_err(|x| x.into()),
+        None => HttpServer::new(app_factory)
+            .listen(listener)?
+            .run()
+            .map_err(|x| x.into()),
+    };
+    let scheme = if tls_mode == tls::TlsMode::Disabled {
+        "http"
+    } else {
+        "https"
+    };
+    info!("Listening on {}://{}:{}", scheme, cloudagent_ip, cloudagent_port);
     try_join!(actix_server, revocation::run_revocation_service())?;
     Ok(())
 }
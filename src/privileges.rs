@@ -0,0 +1,131 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2021 Keylime Authors
+
+//! Drops the agent's privileges once the operations that genuinely need
+//! them - TPM provisioning, mounting the secure tmpfs - are done, so the
+//! long-running HTTP surface and revocation service run least-privilege.
+
+use crate::common::config_get_or;
+use crate::error::{Error, Result};
+use log::info;
+use nix::unistd::{Gid, Group, Uid, User};
+
+/// The UID/GID the agent should run as after privileged initialization,
+/// resolved from `[cloud_agent] run_as_user` / `run_as_group`.
+///
+/// Left unset (the default), [`RunAs::apply`] is a no-op and the agent keeps
+/// running as whatever user launched it, matching prior behavior.
+#[derive(Debug, Clone)]
+pub struct RunAs {
+    user: Option<String>,
+    group: Option<String>,
+}
+
+impl RunAs {
+    /// Read `[cloud_agent] run_as_user` / `run_as_group` from config.
+    pub fn from_config() -> Self {
+        let user = config_get_or("cloud_agent", "run_as_user", "");
+        let group = config_get_or("cloud_agent", "run_as_group", "");
+        RunAs {
+            user: (!user.is_empty()).then_some(user),
+            group: (!group.is_empty()).then_some(group),
+        }
+    }
+
+    /// Drop privileges to the configured group, then the configured user
+    /// (group first, since once the UID changes this process typically
+    /// can no longer change its GID).
+    ///
+    /// Must be called after EK/AK creation and credential activation
+    /// have completed and the secure mount is in place, and before the
+    /// actix server or `revocation::run_revocation_service()` start
+    /// accepting work - everything after this point runs as the
+    /// unprivileged identity.
+    pub fn apply(&self) -> Result<()> {
+        if self.user.is_none() && self.group.is_none() {
+            return Ok(());
+        }
+
+        // Clear supplementary groups *before* changing the primary
+        // gid/uid. setgid/setuid alone leave whatever supplementary
+        // groups the launching identity had - commonly including gid 0 -
+        // attached to the process, which defeats the point of dropping
+        // privileges.
+        nix::unistd::setgroups(&[]).map_err(|e| {
+            Error::Other(format!("setgroups(&[]) failed: {}", e))
+        })?;
+
+        if let Some(group) = &self.group {
+            let gid = resolve_gid(group)?;
+            nix::unistd::setgid(gid).map_err(|e| {
+                Error::Other(format!("setgid({}) failed: {}", group, e))
+            })?;
+            info!("Dropped group privileges to {}", group);
+        }
+
+        if let Some(user) = &self.user {
+            let uid = resolve_uid(user)?;
+            nix::unistd::setuid(uid).map_err(|e| {
+                Error::Other(format!("setuid({}) failed: {}", user, e))
+            })?;
+            info!("Dropped user privileges to {}", user);
+        }
+
+        apply_mac_context();
+        Ok(())
+    }
+}
+
+fn resolve_uid(user: &str) -> Result<Uid> {
+    if let Ok(raw) = user.parse::<u32>() {
+        return Ok(Uid::from_raw(raw));
+    }
+    User::from_name(user)
+        .map_err(|e| Error::Other(format!("looking up user {}: {}", user, e)))?
+        .map(|u| u.uid)
+        .ok_or_else(|| Error::Configuration(format!("no such user: {}", user)))
+}
+
+fn resolve_gid(group: &str) -> Result<Gid> {
+    if let Ok(raw) = group.parse::<u32>() {
+        return Ok(Gid::from_raw(raw));
+    }
+    Group::from_name(group)
+        .map_err(|e| {
+            Error::Other(format!("looking up group {}: {}", group, e))
+        })?
+        .map(|g| g.gid)
+        .ok_or_else(|| Error::Configuration(format!("no such group: {}", group)))
+}
+
+/// Apply an SELinux/seccomp confinement profile if the platform and
+/// config ask for one.
+///
+/// This snapshot only carries the hook point; wiring up an actual
+/// `setexeccon`/`seccomp` profile is left to the platform-specific
+/// `privileges::linux` submodule when one is added.
+fn apply_mac_context() {
+    let context = config_get_or("cloud_agent", "selinux_context", "");
+    if !context.is_empty() {
+        info!(
+            "selinux_context = {} configured but not yet applied by this \
+             build",
+            context
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_as_defaults_to_noop() {
+        // With no [cloud_agent] run_as_user/run_as_group configured,
+        // RunAs must resolve to "do nothing" rather than, say, an empty
+        // string being treated as a real (and invalid) user/group name.
+        let run_as = RunAs::from_config();
+        assert!(run_as.user.is_none());
+        assert!(run_as.group.is_none());
+    }
+}
@@ -0,0 +1,169 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2021 Keylime Authors
+
+//! `/quotes/*` routes: hand the verifier whatever hardware-rooted
+//! evidence this agent is configured to produce, bound to a nonce this
+//! agent itself issued (see [`ChallengeStore`]) so a quote can never be
+//! requested over a nonce of the caller's own choosing.
+
+use crate::common::{self, Challenge, Response};
+use crate::verifier::Registry;
+use actix_web::{http::StatusCode, web, HttpResponse};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How long an issued-but-unredeemed nonce stays valid. Long enough to
+/// cover a verifier's round trip, short enough that a leaked nonce is
+/// useless shortly after.
+const CHALLENGE_TTL: Duration = Duration::from_secs(300);
+
+/// Tracks nonces this agent has issued via `POST /quotes/challenge` but
+/// not yet redeemed, so `/quotes/identity` and `/quotes/integrity` can
+/// refuse to produce evidence over a nonce the caller made up itself.
+#[derive(Default)]
+pub struct ChallengeStore {
+    issued: Mutex<HashMap<String, Instant>>,
+}
+
+impl ChallengeStore {
+    /// Construct an empty store.
+    pub fn new() -> Self {
+        ChallengeStore {
+            issued: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Generate a fresh nonce, remember it, and hand it back to the
+    /// caller as a [`Challenge`].
+    ///
+    /// `/quotes/challenge` takes no authentication, so a caller can ask
+    /// for nonces as fast as it likes; sweeping out anything already
+    /// past [`CHALLENGE_TTL`] before inserting the new one keeps the map
+    /// bounded by how many nonces are outstanding within one TTL window
+    /// rather than growing for as long as the agent runs.
+    pub fn issue(&self) -> Challenge {
+        let nonce = Uuid::new_v4().to_string();
+        let mut issued =
+            self.issued.lock().expect("challenge store lock poisoned");
+        issued.retain(|_, issued_at| issued_at.elapsed() < CHALLENGE_TTL);
+        let _ = issued.insert(nonce.clone(), Instant::now());
+        Challenge { nonce }
+    }
+
+    /// Redeem `nonce`: `true` if this store issued it and it hasn't
+    /// already been redeemed or expired. Single-use - a nonce that
+    /// validates is removed on the way out, so replaying the same
+    /// challenge twice fails the second time.
+    pub fn consume(&self, nonce: &str) -> bool {
+        let mut issued =
+            self.issued.lock().expect("challenge store lock poisoned");
+        match issued.remove(nonce) {
+            Some(issued_at) => issued_at.elapsed() < CHALLENGE_TTL,
+            None => false,
+        }
+    }
+}
+
+/// `POST /quotes/challenge` - the first step of the handshake: issue a
+/// freshness nonce the caller must echo back to `/quotes/identity` or
+/// `/quotes/integrity` to receive evidence bound to it.
+pub async fn challenge(store: web::Data<ChallengeStore>) -> HttpResponse {
+    HttpResponse::Ok().json(store.issue())
+}
+
+async fn respond_to_challenge(
+    registry: &Registry,
+    store: &ChallengeStore,
+    challenge: &Challenge,
+) -> HttpResponse {
+    if !store.consume(&challenge.nonce) {
+        return common::error_response(
+            StatusCode::UNAUTHORIZED,
+            "unknown, already-redeemed, or expired challenge nonce; \
+             request a fresh one from /quotes/challenge",
+        );
+    }
+
+    let nonce = challenge.nonce.as_bytes();
+    let mut evidence = Vec::new();
+    for kind in registry.available_types() {
+        let provider = registry
+            .get(kind)
+            .expect("available_types() only returns registered keys");
+        match provider.get_evidence(nonce).await {
+            Ok(e) => evidence.push(e),
+            Err(e) => {
+                return common::error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    e.to_string(),
+                )
+            }
+        }
+    }
+    HttpResponse::Ok().json(Response { evidence })
+}
+
+/// `POST /quotes/identity` - evidence proving *which* agent this is
+/// (EK/AK-bound), over a nonce previously issued by `/quotes/challenge`,
+/// used by the verifier during initial enrollment.
+pub async fn identity(
+    challenge: web::Json<Challenge>,
+    registry: web::Data<Registry>,
+    store: web::Data<ChallengeStore>,
+) -> HttpResponse {
+    respond_to_challenge(&registry, &store, &challenge).await
+}
+
+/// `POST /quotes/integrity` - evidence covering the running system's
+/// current state (PCRs / measured-boot log / CC report), over a nonce
+/// previously issued by `/quotes/challenge`, polled periodically by the
+/// verifier.
+pub async fn integrity(
+    challenge: web::Json<Challenge>,
+    registry: web::Data<Registry>,
+    store: web::Data<ChallengeStore>,
+) -> HttpResponse {
+    respond_to_challenge(&registry, &store, &challenge).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issued_nonce_can_be_consumed_once() {
+        let store = ChallengeStore::new();
+        let challenge = store.issue();
+        assert!(store.consume(&challenge.nonce));
+        assert!(!store.consume(&challenge.nonce));
+    }
+
+    #[test]
+    fn test_unknown_nonce_is_rejected() {
+        let store = ChallengeStore::new();
+        assert!(!store.consume("never-issued"));
+    }
+
+    #[test]
+    fn test_issue_evicts_expired_entries() {
+        let store = ChallengeStore::new();
+        let stale = store.issue();
+        {
+            let mut issued =
+                store.issued.lock().expect("challenge store lock poisoned"); //#[allow_ci]
+            let _ = issued.insert(
+                stale.nonce.clone(),
+                Instant::now() - CHALLENGE_TTL - Duration::from_secs(1),
+            );
+        }
+        let _fresh = store.issue();
+        assert!(!store
+            .issued
+            .lock()
+            .expect("challenge store lock poisoned") //#[allow_ci]
+            .contains_key(&stale.nonce));
+        assert!(!store.consume(&stale.nonce));
+    }
+}
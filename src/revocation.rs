@@ -0,0 +1,84 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2021 Keylime Authors
+
+//! Listens for revocation actions pushed down from the verifier and
+//! executes them.
+//!
+//! This is a high-value target: whatever shows up here runs on the
+//! agent's behalf, so before dispatch every action is checked against a
+//! TUF-signed, versioned metadata tree (see [`crate::tuf`]) instead of
+//! being trusted just because it arrived over an authenticated channel.
+
+use crate::cmd_exec;
+use crate::common::config_get_or;
+use crate::error::{Error, Result};
+use crate::tuf;
+use log::{info, warn};
+use serde::Deserialize;
+
+/// A single revocation instruction as delivered to the agent: which
+/// script/allowlist to run and where to fetch its signed payload from.
+#[derive(Debug, Deserialize)]
+pub struct RevocationAction {
+    /// TUF target path identifying the payload, e.g.
+    /// `"revocation_actions/local_action_rebuild_policy.py"`.
+    pub target_path: String,
+    /// The action payload itself, as delivered alongside this message.
+    pub payload: Vec<u8>,
+}
+
+/// Run a single action once its payload has been verified against the
+/// current TUF targets metadata.
+async fn dispatch(
+    tuf_client: &tuf::Client,
+    action: RevocationAction,
+) -> Result<()> {
+    tuf_client.verify_target(&action.target_path, &action.payload)?;
+    info!("Executing verified revocation action {}", action.target_path);
+    cmd_exec::run_script(&action.target_path, &action.payload)
+}
+
+/// Main loop: keep the TUF client's metadata fresh and act on revocation
+/// messages as they arrive.
+///
+/// Runs for the lifetime of the agent, alongside the actix server, via
+/// `try_join!` in `main()`.
+pub async fn run_revocation_service() -> Result<()> {
+    let mut tuf_client = tuf::Client::from_config()?;
+
+    loop {
+        if let Err(e) = tuf_client.refresh().await {
+            warn!(
+                "could not refresh TUF metadata, revocation actions will \
+                 be rejected until it succeeds: {}",
+                e
+            );
+        }
+
+        match receive_action().await {
+            Ok(Some(action)) => {
+                if let Err(e) = dispatch(&tuf_client, action).await {
+                    warn!("revocation action rejected: {}", e);
+                }
+            }
+            Ok(None) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Block for the next revocation message from the verifier's
+/// notification channel at `[revocation] revocation_notifier_ip`.
+///
+/// Upstream Keylime subscribes to the verifier over ZeroMQ; that
+/// transport is a separate dependency this snapshot doesn't carry, so
+/// this always reports "nothing yet" after a wait rather than pretending
+/// to have received (and therefore silently discarded) a message. The
+/// `tuf_client.refresh()` call in [`run_revocation_service`] keeps
+/// running regardless, so metadata stays current for whenever the
+/// transport is wired in.
+async fn receive_action() -> Result<Option<RevocationAction>> {
+    let _addr = config_get_or("revocation", "revocation_notifier_ip", "");
+    tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+    Ok(None)
+}
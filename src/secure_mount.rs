@@ -0,0 +1,60 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2021 Keylime Authors
+
+//! Sets up the `tmpfs` mount under which the agent keeps key material that
+//! must never touch persistent storage.
+
+use crate::common::config_get_or;
+use crate::error::{Error, Result};
+use nix::mount::{mount, MsFlags};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// Mount a `tmpfs` (sized from `[cloud_agent] secure_size`, default `1m`)
+/// at the configured secure mount point, locked down to mode `0700`, and
+/// return its path.
+///
+/// Everything the agent is not willing to let survive a reboot or a stolen
+/// disk - the unwrapped U key, ACME account keys, TUF client state - is
+/// written under the path this returns.
+pub fn mount() -> Result<PathBuf> {
+    let dir =
+        config_get_or("cloud_agent", "secure_mount", "/var/lib/keylime/secure");
+    let size = config_get_or("cloud_agent", "secure_size", "1m");
+    let path = PathBuf::from(dir);
+    std::fs::create_dir_all(&path)?;
+
+    if !is_mounted(&path)? {
+        let data = format!("size={},mode=0700", size);
+        mount(
+            Some("tmpfs"),
+            &path,
+            Some("tmpfs"),
+            MsFlags::MS_NODEV | MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC,
+            Some(data.as_str()),
+        )
+        .map_err(|e| {
+            Error::Other(format!(
+                "mounting tmpfs at {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+    }
+
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o700))?;
+    Ok(path)
+}
+
+/// Whether `path` is already a mount point of its own filesystem, rather
+/// than a plain directory on its parent's - checked so calling [`mount`]
+/// a second time (e.g. a renewal path re-running after the agent already
+/// started) doesn't try to stack a second tmpfs on top of the first.
+fn is_mounted(path: &Path) -> Result<bool> {
+    let meta = std::fs::metadata(path)?;
+    let parent = path.parent().ok_or_else(|| {
+        Error::Other(format!("{} has no parent directory", path.display()))
+    })?;
+    let parent_meta = std::fs::metadata(parent)?;
+    Ok(meta.dev() != parent_meta.dev())
+}
@@ -0,0 +1,216 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2021 Keylime Authors
+
+//! TLS provisioning for the agent's HTTPS listener.
+//!
+//! The agent can run in three modes, selected by `[cloud_agent]
+//! tls_mode`:
+//!
+//! * `"disabled"` (default, back-compat) - plain HTTP, as before.
+//! * `"operator"` - an operator-supplied cert/key pair is loaded from
+//!   `[cloud_agent] tls_cert` / `tls_key`.
+//! * `"acme"` - a certificate is obtained and kept renewed via
+//!   [`crate::acme`].
+
+use crate::acme::{self, AcmeConfig, IssuedCert};
+use crate::common::config_get_or;
+use crate::error::{Error, Result};
+use log::{info, warn};
+use openssl::x509::X509;
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// How long before a certificate's `notAfter` the renewal task tries to
+/// replace it.
+const RENEWAL_MARGIN: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// How often the renewal task wakes up to check the current certificate's
+/// remaining lifetime.
+const RENEWAL_POLL_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How the agent's HTTPS listener should obtain its certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsMode {
+    /// Serve plain HTTP, matching the agent's historical behavior.
+    Disabled,
+    /// Load a cert/key pair the operator placed on disk.
+    Operator,
+    /// Obtain and renew a certificate automatically via ACME.
+    Acme,
+}
+
+impl TlsMode {
+    /// Read `[cloud_agent] tls_mode` (defaulting to `"disabled"` so
+    /// upgrading an existing install doesn't suddenly require a cert).
+    pub fn from_config() -> Self {
+        Self::parse(&config_get_or("cloud_agent", "tls_mode", "disabled"))
+    }
+
+    /// Parse a raw `tls_mode` value, defaulting anything unrecognized
+    /// (including an unset/empty value) to [`TlsMode::Disabled`]. Split
+    /// out from [`TlsMode::from_config`] so the mapping can be
+    /// unit-tested without a config file.
+    fn parse(raw: &str) -> Self {
+        match raw {
+            "operator" => TlsMode::Operator,
+            "acme" => TlsMode::Acme,
+            _ => TlsMode::Disabled,
+        }
+    }
+}
+
+/// In-memory certificate store shared with the background renewal task,
+/// which swaps a renewed chain/key in behind an `RwLock`. The listener's
+/// `ServerConfig` is built once in [`setup`] from the chain/key current at
+/// that point and does not read this store afterwards, so a renewed
+/// certificate is not served until the agent restarts.
+#[derive(Clone)]
+pub struct CertStore {
+    inner: Arc<RwLock<(Vec<Certificate>, PrivateKey)>>,
+}
+
+impl CertStore {
+    fn new(chain: Vec<Certificate>, key: PrivateKey) -> Self {
+        CertStore {
+            inner: Arc::new(RwLock::new((chain, key))),
+        }
+    }
+
+    /// Snapshot the currently active certificate chain and key.
+    pub fn current(&self) -> (Vec<Certificate>, PrivateKey) {
+        let guard = self.inner.read().expect("cert store lock poisoned");
+        guard.clone()
+    }
+
+    fn replace(&self, chain: Vec<Certificate>, key: PrivateKey) {
+        let mut guard =
+            self.inner.write().expect("cert store lock poisoned");
+        *guard = (chain, key);
+    }
+}
+
+fn issued_to_rustls(issued: IssuedCert) -> Result<(Vec<Certificate>, PrivateKey)> {
+    let chain = issued
+        .chain
+        .iter()
+        .map(|c| c.to_der().map(Certificate))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let key = PrivateKey(issued.key.private_key_to_der()?);
+    Ok((chain, key))
+}
+
+fn load_operator_cert() -> Result<(Vec<Certificate>, PrivateKey)> {
+    let cert_path = config_get_or("cloud_agent", "tls_cert", "");
+    let key_path = config_get_or("cloud_agent", "tls_key", "");
+    if cert_path.is_empty() || key_path.is_empty() {
+        return Err(Error::Configuration(
+            "tls_mode = operator requires [cloud_agent] tls_cert and \
+             tls_key"
+                .to_string(),
+        ));
+    }
+    let cert_pem = std::fs::read(&cert_path)?;
+    let key_pem = std::fs::read(&key_path)?;
+    let chain = X509::stack_from_pem(&cert_pem)?
+        .iter()
+        .map(|c| c.to_der().map(Certificate))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let key =
+        PrivateKey(openssl::pkey::PKey::private_key_from_pem(&key_pem)?
+            .private_key_to_der()?);
+    Ok((chain, key))
+}
+
+/// Resolve the initial certificate according to `mode` and, for ACME,
+/// spawn the background task that keeps it renewed.
+///
+/// Returns `None` when `mode` is [`TlsMode::Disabled`], in which case the
+/// caller should bind plain HTTP as before.
+pub async fn setup(mode: TlsMode) -> Result<Option<rustls::ServerConfig>> {
+    let (chain, key) = match mode {
+        TlsMode::Disabled => return Ok(None),
+        TlsMode::Operator => load_operator_cert()?,
+        TlsMode::Acme => {
+            let cfg = AcmeConfig::from_config();
+            let issued = acme::obtain_certificate(&cfg).await?;
+            issued_to_rustls(issued)?
+        }
+    };
+
+    let store = CertStore::new(chain, key);
+    if mode == TlsMode::Acme {
+        tokio::spawn(renewal_task(store.clone()));
+    }
+
+    let (chain, key) = store.current();
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .map_err(|e| {
+            Error::Other(format!("invalid TLS certificate/key: {}", e))
+        })?;
+    Ok(Some(config))
+}
+
+/// Background loop that wakes up periodically and re-issues the
+/// certificate via ACME once it is within [`RENEWAL_MARGIN`] of expiry.
+async fn renewal_task(store: CertStore) {
+    loop {
+        tokio::time::sleep(RENEWAL_POLL_INTERVAL).await;
+
+        let expires_soon = {
+            let (chain, _) = store.current();
+            match chain.first().map(|c| X509::from_der(&c.0)) {
+                Some(Ok(cert)) => {
+                    let remaining = openssl::asn1::Asn1Time::days_from_now(0)
+                        .expect("clock is sane")
+                        .diff(cert.not_after())
+                        .ok();
+                    remaining
+                        .map(|d| {
+                            Duration::from_secs(
+                                (d.days.max(0) as u64) * 24 * 60 * 60,
+                            ) < RENEWAL_MARGIN
+                        })
+                        .unwrap_or(true)
+                }
+                _ => true,
+            }
+        };
+
+        if !expires_soon {
+            continue;
+        }
+
+        info!("TLS certificate nearing expiry, renewing via ACME");
+        let cfg = AcmeConfig::from_config();
+        match acme::obtain_certificate(&cfg).await {
+            Ok(issued) => match issued_to_rustls(issued) {
+                Ok((chain, key)) => store.replace(chain, key),
+                Err(e) => {
+                    warn!("renewed certificate was unusable: {}", e)
+                }
+            },
+            Err(e) => warn!("ACME renewal failed, will retry: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tls_mode_parse_known_values() {
+        assert_eq!(TlsMode::parse("operator"), TlsMode::Operator);
+        assert_eq!(TlsMode::parse("acme"), TlsMode::Acme);
+        assert_eq!(TlsMode::parse("disabled"), TlsMode::Disabled);
+    }
+
+    #[test]
+    fn test_tls_mode_parse_defaults_to_disabled() {
+        assert_eq!(TlsMode::parse(""), TlsMode::Disabled);
+        assert_eq!(TlsMode::parse("garbage"), TlsMode::Disabled);
+    }
+}
@@ -0,0 +1,184 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2021 Keylime Authors
+
+//! Thin wrapper around `tss_esapi` for the handful of TPM 2.0 operations
+//! the agent needs: opening a context, provisioning the EK/AK, activating
+//! the credential the registrar sends back, and producing quotes.
+
+use crate::error::{Error, Result};
+use tss_esapi::{
+    abstraction::{ak, ek, DefaultKey},
+    constants::algorithm::{AsymmetricAlgorithm, HashingAlgorithm},
+    handles::KeyHandle,
+    interface_types::algorithm::SignatureSchemeAlgorithm,
+    structures::{
+        Digest, EncryptedSecret, IdObject, PcrSelectionListBuilder,
+        PcrSlot, Public,
+    },
+    tcti_ldr::TabrmdConfig,
+    traits::Marshall,
+    Context, TctiNameConf,
+};
+
+/// A TPM2B_PUBLIC public area, kept as the library's own structured type
+/// rather than re-marshaled bytes since every caller just forwards it to
+/// the registrar, which marshals it itself.
+pub type Tpm2bPublic = Public;
+
+/// The PCR bank the agent quotes over. Matches what upstream Keylime
+/// measures into by default; not yet configurable.
+const QUOTE_PCRS: &[PcrSlot] = &[
+    PcrSlot::Slot0,
+    PcrSlot::Slot1,
+    PcrSlot::Slot2,
+    PcrSlot::Slot3,
+    PcrSlot::Slot4,
+    PcrSlot::Slot5,
+    PcrSlot::Slot6,
+    PcrSlot::Slot7,
+    PcrSlot::Slot8,
+    PcrSlot::Slot9,
+    PcrSlot::Slot10,
+];
+
+/// Open a context against the TPM (or software emulator) configured for
+/// this host via the TCTI environment.
+pub fn get_tpm2_ctx() -> Result<Context> {
+    let tcti = TctiNameConf::from_environment_variable()
+        .unwrap_or_else(|_| TctiNameConf::Tabrmd(TabrmdConfig::default()));
+    Ok(Context::new(tcti)?)
+}
+
+/// Create (or load, if already persisted) the agent's Endorsement Key.
+///
+/// Returns the transient handle, the EK certificate read out of NV, and
+/// the public area to hand to the registrar.
+pub fn create_ek(
+    ctx: &mut Context,
+    alg: Option<AsymmetricAlgorithm>,
+) -> Result<(KeyHandle, Vec<u8>, Tpm2bPublic)> {
+    let alg = alg.unwrap_or(AsymmetricAlgorithm::Rsa);
+    let ek_handle = ek::create_ek_object(ctx, alg, DefaultKey)?;
+    let (public, _, _) = ctx.read_public(ek_handle)?;
+    let ek_cert = ek::retrieve_ek_pubcert(ctx, alg)?;
+    Ok((ek_handle, ek_cert, public))
+}
+
+/// Create the Attestation Identity Key under `ek_handle` and load it so
+/// it's ready to sign quotes and have credentials activated against it.
+pub fn create_ak(
+    ctx: &mut Context,
+    ek_handle: KeyHandle,
+) -> Result<(KeyHandle, Vec<u8>, Tpm2bPublic)> {
+    let ak = ak::create_ak(
+        ctx,
+        ek_handle,
+        HashingAlgorithm::Sha256,
+        SignatureSchemeAlgorithm::RsaSsa,
+        None,
+        DefaultKey,
+    )?;
+    let ak_handle = ak::load_ak(
+        ctx,
+        ek_handle,
+        None,
+        ak.out_private.clone(),
+        ak.out_public.clone(),
+    )?;
+    Ok((ak_handle, ak.out_name.value().to_vec(), ak.out_public))
+}
+
+/// Run `TPM2_ActivateCredential` against the keyblob the registrar sent,
+/// yielding the session key it encrypted.
+///
+/// `keyblob` is the wire-format concatenation the registrar sends:
+/// a `TPM2B_ID_OBJECT` followed by a `TPM2B_ENCRYPTED_SECRET`.
+pub fn activate_credential(
+    ctx: &mut Context,
+    keyblob: Vec<u8>,
+    ak_handle: KeyHandle,
+    ek_handle: KeyHandle,
+) -> Result<Digest> {
+    let (credential_blob, secret) = split_keyblob(&keyblob)?;
+    Ok(ctx.execute_with_nullauth_session(|ctx| {
+        ctx.activate_credential(ak_handle, ek_handle, credential_blob, secret)
+    })?)
+}
+
+/// Split the registrar's keyblob into the `TPM2B_ID_OBJECT` and
+/// `TPM2B_ENCRYPTED_SECRET` `TPM2_ActivateCredential` expects, each
+/// length-prefixed the way the rest of the wire protocol marshals
+/// `TPM2B_*` structures.
+fn split_keyblob(keyblob: &[u8]) -> Result<(IdObject, EncryptedSecret)> {
+    if keyblob.len() < 4 {
+        return Err(Error::Other(
+            "keyblob too short to contain a TPM2B_ID_OBJECT length prefix"
+                .to_string(),
+        ));
+    }
+    let id_object_len =
+        u16::from_be_bytes([keyblob[0], keyblob[1]]) as usize;
+    let id_object_end = 2 + id_object_len;
+    if keyblob.len() < id_object_end {
+        return Err(Error::Other(
+            "keyblob shorter than its declared TPM2B_ID_OBJECT length"
+                .to_string(),
+        ));
+    }
+    let id_object =
+        IdObject::try_from(keyblob[2..id_object_end].to_vec())
+            .map_err(|e| Error::Other(format!("invalid TPM2B_ID_OBJECT: {}", e)))?;
+    let rest = &keyblob[id_object_end..];
+    if rest.len() < 2 {
+        return Err(Error::Other(
+            "keyblob too short to contain a TPM2B_ENCRYPTED_SECRET length prefix"
+                .to_string(),
+        ));
+    }
+    let secret_len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+    if rest.len() < 2 + secret_len {
+        return Err(Error::Other(
+            "keyblob shorter than its declared TPM2B_ENCRYPTED_SECRET length"
+                .to_string(),
+        ));
+    }
+    let secret = EncryptedSecret::try_from(rest[2..2 + secret_len].to_vec())
+        .map_err(|e| {
+            Error::Other(format!("invalid TPM2B_ENCRYPTED_SECRET: {}", e))
+        })?;
+    Ok((id_object, secret))
+}
+
+/// Produce a `TPM2_Quote` over [`QUOTE_PCRS`] with `nonce` set as the
+/// qualifying data, so the signature is bound to that exact nonce and
+/// can't be replayed against a different challenge. `ak_handle` must
+/// already be loaded in `ctx` (see [`create_ak`]).
+///
+/// Returns `(attest, signature)`, both TPM-wire encoded, ready to be
+/// forwarded to a remote verifier as-is.
+pub fn quote(
+    ctx: &mut Context,
+    ak_handle: KeyHandle,
+    nonce: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let pcr_selection = PcrSelectionListBuilder::new()
+        .with_selection(
+            tss_esapi::interface_types::algorithm::HashingAlgorithm::Sha256,
+            QUOTE_PCRS,
+        )
+        .build()
+        .map_err(|e| {
+            Error::Other(format!("building PCR selection failed: {}", e))
+        })?;
+    let qualifying_data = Digest::try_from(nonce.to_vec())
+        .map_err(|e| Error::Other(format!("nonce too long for a quote: {}", e)))?;
+    let (attest, signature) = ctx.execute_with_nullauth_session(|ctx| {
+        ctx.quote(
+            ak_handle,
+            qualifying_data,
+            Default::default(),
+            pcr_selection,
+        )
+    })?;
+    Ok((attest.marshall()?, signature.marshall()?))
+}
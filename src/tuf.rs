@@ -0,0 +1,380 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2021 Keylime Authors
+
+//! A small TUF (The Update Framework, https://theupdateframework.io/)
+//! client used to verify revocation metadata and payloads before
+//! [`crate::revocation`] trusts them.
+//!
+//! Only the four top-level roles the spec calls "the client workflow"
+//! need: `root`, `timestamp`, `snapshot`, `targets`. Delegated targets
+//! aren't supported since the revocation repository this protects is
+//! small and flat.
+
+use crate::common::config_get_or;
+use crate::error::{Error, Result};
+use chrono::{DateTime, Utc};
+use openssl::pkey::{Id, PKey};
+use openssl::sign::Verifier;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Root keys pinned in config (`[revocation] tuf_root_keys`, a
+/// comma-separated list of hex-encoded Ed25519 public keys) rather than
+/// trusted-on-first-use, so a compromised or spoofed distribution server
+/// can't bootstrap a new root of trust.
+#[derive(Debug, Clone)]
+pub struct PinnedRoot {
+    keys: Vec<Vec<u8>>,
+    threshold: usize,
+}
+
+/// Parse the comma-separated, hex-encoded key list from `[revocation]
+/// tuf_root_keys` into raw key bytes. Split out from [`PinnedRoot::from_config`]
+/// so the parsing itself can be unit-tested without a config file.
+fn parse_root_keys(raw: &str) -> Result<Vec<Vec<u8>>> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(hex::decode)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| {
+            Error::Configuration(format!("malformed tuf_root_keys: {}", e))
+        })
+}
+
+impl PinnedRoot {
+    /// Load pinned root keys and signing threshold from config.
+    pub fn from_config() -> Result<Self> {
+        let raw = config_get_or("revocation", "tuf_root_keys", "");
+        let keys = parse_root_keys(&raw)?;
+        if keys.is_empty() {
+            return Err(Error::Configuration(
+                "[revocation] tuf_root_keys must list at least one \
+                 pinned root key"
+                    .to_string(),
+            ));
+        }
+        let threshold: usize = config_get_or(
+            "revocation",
+            "tuf_root_threshold",
+            "1",
+        )
+        .parse()
+        .unwrap_or(1);
+        Ok(PinnedRoot { keys, threshold })
+    }
+}
+
+/// A signed TUF metadata role as it comes over the wire: the signed
+/// payload plus the detached signatures over it.
+#[derive(Debug, Deserialize)]
+pub struct SignedMetadata {
+    signed: RoleContent,
+    signatures: Vec<Signature>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Signature {
+    keyid: String,
+    sig: String,
+}
+
+/// The fields common to every role's signed content; `version` and
+/// `expires` are what give us rollback protection.
+#[derive(Debug, Deserialize)]
+pub struct RoleContent {
+    #[serde(rename = "_type")]
+    role_type: String,
+    version: u64,
+    expires: String,
+    /// For `targets`: path -> hex sha256 of the target file.
+    #[serde(default)]
+    targets: HashMap<String, String>,
+}
+
+/// Versions of timestamp/snapshot/targets we've already accepted, kept
+/// across calls so a replayed, older-but-validly-signed metadata bundle
+/// is rejected even though its signature checks out.
+#[derive(Debug, Default)]
+pub struct RollbackState {
+    timestamp_version: u64,
+    snapshot_version: u64,
+    targets_version: u64,
+}
+
+/// TUF client: fetches and verifies the metadata tree rooted at
+/// `base_url`, then tells the caller whether a given revocation payload
+/// is safe to act on.
+pub struct Client {
+    base_url: String,
+    root: PinnedRoot,
+    state: RollbackState,
+    /// Path -> hex sha256, copied out of the most recently verified
+    /// `targets.json`. Cleared at the start of every [`Client::refresh`],
+    /// so it is empty whenever the latest refresh did not succeed.
+    verified_targets: HashMap<String, String>,
+}
+
+impl Client {
+    /// Build a client from `[revocation] tuf_repo_url` and the pinned
+    /// root keys in config.
+    pub fn from_config() -> Result<Self> {
+        let base_url = config_get_or(
+            "revocation",
+            "tuf_repo_url",
+            "",
+        );
+        if base_url.is_empty() {
+            return Err(Error::Configuration(
+                "[revocation] tuf_repo_url is required when TUF \
+                 verification is enabled"
+                    .to_string(),
+            ));
+        }
+        Ok(Client {
+            base_url,
+            root: PinnedRoot::from_config()?,
+            state: RollbackState::default(),
+            verified_targets: HashMap::new(),
+        })
+    }
+
+    fn verify_signatures(
+        &self,
+        meta: &SignedMetadata,
+        raw_signed: &[u8],
+    ) -> Result<()> {
+        let valid = meta
+            .signatures
+            .iter()
+            .filter(|sig| {
+                self.root.keys.iter().any(|k| {
+                    hex::encode(k) == sig.keyid
+                        && verify_ed25519(k, raw_signed, &sig.sig)
+                })
+            })
+            .count();
+        if valid < self.root.threshold {
+            return Err(Error::Other(format!(
+                "TUF metadata signed by {} of {} required root keys",
+                valid, self.root.threshold
+            )));
+        }
+        Ok(())
+    }
+
+    /// Reject a role whose `expires` has already passed. A metadata file
+    /// that is both correctly signed *and* expired must still be
+    /// rejected - that's what stops a captured-but-stale signed bundle
+    /// from being replayed forever.
+    fn check_not_expired(&self, content: &RoleContent) -> Result<()> {
+        let expires =
+            DateTime::parse_from_rfc3339(&content.expires).map_err(|e| {
+                Error::Other(format!(
+                    "TUF {} role has an unparsable expires timestamp {:?}: {}",
+                    content.role_type, content.expires, e
+                ))
+            })?;
+        if expires < Utc::now() {
+            return Err(Error::Other(format!(
+                "TUF {} role expired at {}",
+                content.role_type, content.expires
+            )));
+        }
+        Ok(())
+    }
+
+    /// Verify `role`'s signatures meet the pinned threshold and its
+    /// expiry hasn't passed. Shared by every role check in [`refresh`]
+    /// so a role can never be accepted on version number alone.
+    fn verify_role(&self, role: &SignedMetadata, raw: &[u8]) -> Result<()> {
+        self.verify_signatures(role, raw)?;
+        self.check_not_expired(&role.signed)
+    }
+
+    /// Fetch and verify `timestamp.json`, `snapshot.json`, then
+    /// `targets.json`, enforcing for each that its signatures meet the
+    /// pinned threshold, that it isn't expired, and that its `version`
+    /// only ever moves forward - this is what stops a captured older
+    /// (but validly signed) bundle from being replayed.
+    pub async fn refresh(&mut self) -> Result<()> {
+        self.verified_targets.clear();
+        let (timestamp, raw) = self.fetch_role("timestamp.json").await?;
+        self.verify_role(&timestamp, &raw)?;
+        if timestamp.signed.version < self.state.timestamp_version {
+            return Err(Error::Other(
+                "TUF timestamp rollback detected".to_string(),
+            ));
+        }
+        self.state.timestamp_version = timestamp.signed.version;
+
+        let (snapshot, raw) = self.fetch_role("snapshot.json").await?;
+        self.verify_role(&snapshot, &raw)?;
+        if snapshot.signed.version < self.state.snapshot_version {
+            return Err(Error::Other(
+                "TUF snapshot rollback detected".to_string(),
+            ));
+        }
+        self.state.snapshot_version = snapshot.signed.version;
+
+        let (targets, raw) = self.fetch_role("targets.json").await?;
+        self.verify_role(&targets, &raw)?;
+        if targets.signed.version < self.state.targets_version {
+            return Err(Error::Other(
+                "TUF targets rollback detected".to_string(),
+            ));
+        }
+        self.state.targets_version = targets.signed.version;
+        self.verified_targets = targets.signed.targets;
+
+        Ok(())
+    }
+
+    /// Fetch a role's raw bytes (for signature verification) alongside
+    /// its parsed form.
+    ///
+    /// This is the one piece of the client that actually needs an
+    /// HTTP(S) GET of `{base_url}/{name}`, and this snapshot carries no
+    /// HTTP client dependency to issue it - every other check in this
+    /// module (signature verification, expiry, rollback, target hash
+    /// matching) is fully implemented and runs the moment this returns
+    /// real bytes.
+    async fn fetch_role(&self, name: &str) -> Result<(SignedMetadata, Vec<u8>)> {
+        Err(Error::Other(format!(
+            "fetching {} from {} requires an HTTP client this build \
+             doesn't carry",
+            name, self.base_url
+        )))
+    }
+
+    /// Verify that `payload`'s hash matches the most recently verified
+    /// `targets.json` entry for `target_path`. Callers must have called
+    /// [`Client::refresh`] recently enough that the timestamp role
+    /// hasn't expired.
+    pub fn verify_target(
+        &self,
+        target_path: &str,
+        payload: &[u8],
+    ) -> Result<()> {
+        let expected = self.verified_targets.get(target_path).ok_or_else(|| {
+            Error::Other(format!(
+                "{} is not listed in the verified targets metadata",
+                target_path
+            ))
+        })?;
+        let actual = hex::encode(openssl::sha::sha256(payload));
+        if &actual != expected {
+            return Err(Error::Other(format!(
+                "{} hash mismatch: targets metadata says {}, payload is {}",
+                target_path, expected, actual
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Verify an Ed25519 signature over `message`: `pubkey` is the raw
+/// 32-byte key, `sig_hex` the hex-encoded 64-byte signature, both as
+/// they appear in TUF metadata.
+fn verify_ed25519(pubkey: &[u8], message: &[u8], sig_hex: &str) -> bool {
+    let sig = match hex::decode(sig_hex) {
+        Ok(sig) => sig,
+        Err(_) => return false,
+    };
+    let key = match PKey::public_key_from_raw_bytes(pubkey, Id::ED25519) {
+        Ok(key) => key,
+        Err(_) => return false,
+    };
+    let mut verifier = match Verifier::new_without_digest(&key) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+    verifier.verify_oneshot(&sig, message).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_root_keys() {
+        let keys = parse_root_keys(" deadbeef, 0011 ").unwrap();
+        assert_eq!(keys, vec![vec![0xde, 0xad, 0xbe, 0xef], vec![0x00, 0x11]]);
+    }
+
+    #[test]
+    fn test_parse_root_keys_rejects_bad_hex() {
+        assert!(parse_root_keys("not-hex").is_err());
+    }
+
+    #[test]
+    fn test_parse_root_keys_empty_is_empty() {
+        assert!(parse_root_keys("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_verify_ed25519_roundtrip() {
+        let key = PKey::generate_ed25519().unwrap(); //#[allow_ci]
+        let message = b"some TUF role bytes";
+        let mut signer =
+            openssl::sign::Signer::new_without_digest(&key).unwrap(); //#[allow_ci]
+        let sig = signer.sign_oneshot_to_vec(message).unwrap(); //#[allow_ci]
+        let pubkey = key.raw_public_key().unwrap(); //#[allow_ci]
+        assert!(verify_ed25519(&pubkey, message, &hex::encode(sig)));
+    }
+
+    #[test]
+    fn test_verify_ed25519_rejects_wrong_message() {
+        let key = PKey::generate_ed25519().unwrap(); //#[allow_ci]
+        let mut signer =
+            openssl::sign::Signer::new_without_digest(&key).unwrap(); //#[allow_ci]
+        let sig = signer.sign_oneshot_to_vec(b"original").unwrap(); //#[allow_ci]
+        let pubkey = key.raw_public_key().unwrap(); //#[allow_ci]
+        assert!(!verify_ed25519(&pubkey, b"tampered", &hex::encode(sig)));
+    }
+
+    fn test_client() -> Client {
+        Client {
+            base_url: "https://example.invalid".to_string(),
+            root: PinnedRoot {
+                keys: vec![],
+                threshold: 1,
+            },
+            state: RollbackState::default(),
+            verified_targets: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_verify_target_matches_hash() {
+        let mut client = test_client();
+        let payload = b"revocation script contents";
+        let hash = hex::encode(openssl::sha::sha256(payload));
+        let _ = client
+            .verified_targets
+            .insert("revocation_actions/foo.py".to_string(), hash);
+        assert!(client
+            .verify_target("revocation_actions/foo.py", payload)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_verify_target_rejects_hash_mismatch() {
+        let mut client = test_client();
+        let _ = client.verified_targets.insert(
+            "revocation_actions/foo.py".to_string(),
+            hex::encode(openssl::sha::sha256(b"expected")),
+        );
+        assert!(client
+            .verify_target("revocation_actions/foo.py", b"tampered")
+            .is_err());
+    }
+
+    #[test]
+    fn test_verify_target_rejects_unknown_path() {
+        let client = test_client();
+        assert!(client
+            .verify_target("revocation_actions/unknown.py", b"payload")
+            .is_err());
+    }
+}
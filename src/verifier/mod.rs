@@ -0,0 +1,122 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2021 Keylime Authors
+
+//! Driver framework for hardware-rooted attestation evidence.
+//!
+//! `quotes_handler` used to assume a single TPM2 quote path. This module
+//! introduces an [`EvidenceProvider`] trait so additional evidence types
+//! (confidential-computing attestation reports, in time) can be added as
+//! their own driver module without touching the handlers - each driver
+//! registers itself in a [`Registry`] keyed by evidence type, and the
+//! handlers dispatch through that registry instead of calling `tpm::`
+//! directly.
+
+mod tpm_provider;
+
+pub use tpm_provider::TpmEvidenceProvider;
+
+use crate::common::config_get_or;
+use crate::error::{Error, Result};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A single piece of hardware-rooted evidence, tagged with the kind of
+/// driver that produced it so a remote verifier can pick the right
+/// validation path without out-of-band negotiation.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "evidence_type", rename_all = "snake_case")]
+pub enum Evidence {
+    /// A TPM2 quote (`TPMS_ATTEST` + signature) over the supplied nonce.
+    Tpm {
+        /// TPM-wire marshalled `TPMS_ATTEST`.
+        quote: Vec<u8>,
+        /// Signature over `quote`.
+        signature: Vec<u8>,
+    },
+    /// A confidential-computing attestation report, kept opaque since its
+    /// shape is defined by the vendor's driver, not by this module.
+    ConfidentialComputing {
+        /// Name of the CC platform the report came from (e.g. `"sev-snp"`,
+        /// `"tdx"`), used by the remote verifier to select a parser.
+        platform: String,
+        /// Raw, vendor-defined report bytes.
+        report: Vec<u8>,
+    },
+}
+
+/// Implemented by each evidence driver. `evidence_type` must be unique
+/// across the registry and is what callers use to ask for a specific
+/// driver (and what config uses to select which drivers run).
+#[async_trait]
+pub trait EvidenceProvider: Send + Sync {
+    /// Stable identifier for this driver, e.g. `"tpm"`.
+    fn evidence_type(&self) -> &'static str;
+
+    /// Produce evidence binding `nonce`, proving it was generated after
+    /// the nonce was issued (see the challenge/response handshake in
+    /// `quotes_handler`).
+    async fn get_evidence(&self, nonce: &[u8]) -> Result<Evidence>;
+}
+
+/// Lookup table of the evidence drivers active on this agent, built once
+/// at startup from `[cloud_agent] evidence_types` and consulted by
+/// `quotes_handler` on every request.
+#[derive(Clone, Default)]
+pub struct Registry {
+    providers: HashMap<&'static str, Arc<dyn EvidenceProvider>>,
+}
+
+impl Registry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Registry {
+            providers: HashMap::new(),
+        }
+    }
+
+    /// Register `provider`, keyed by its own [`EvidenceProvider::evidence_type`].
+    pub fn register(&mut self, provider: Arc<dyn EvidenceProvider>) {
+        let _ = self
+            .providers
+            .insert(provider.evidence_type(), provider);
+    }
+
+    /// Look up a previously registered driver by type name.
+    pub fn get(&self, evidence_type: &str) -> Option<Arc<dyn EvidenceProvider>> {
+        self.providers.get(evidence_type).cloned()
+    }
+
+    /// Every evidence type this agent can currently produce. The order is
+    /// not defined (the drivers are kept in a `HashMap`).
+    pub fn available_types(&self) -> Vec<&'static str> {
+        self.providers.keys().copied().collect()
+    }
+}
+
+/// Build the registry this agent runs with, from `[cloud_agent]
+/// evidence_types` (a comma-separated list, defaulting to just `"tpm"` so
+/// existing installs keep behaving exactly as before). `ak_handle` is the
+/// AK `main()` already provisioned at startup, handed to whichever
+/// drivers need it.
+pub fn build_registry(
+    ak_handle: tss_esapi::handles::KeyHandle,
+) -> Result<Registry> {
+    let mut registry = Registry::new();
+    let configured = config_get_or("cloud_agent", "evidence_types", "tpm");
+    for kind in configured.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        match kind {
+            "tpm" => registry
+                .register(Arc::new(TpmEvidenceProvider::new(ak_handle))),
+            other => {
+                return Err(Error::Configuration(format!(
+                    "unknown evidence_type \"{}\" in [cloud_agent] \
+                     evidence_types",
+                    other
+                )))
+            }
+        }
+    }
+    Ok(registry)
+}
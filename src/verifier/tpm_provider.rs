@@ -0,0 +1,40 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2021 Keylime Authors
+
+//! The original (and, today, default) evidence driver: a TPM2 quote.
+
+use super::{Evidence, EvidenceProvider};
+use crate::error::Result;
+use crate::tpm;
+use async_trait::async_trait;
+use tss_esapi::handles::KeyHandle;
+
+/// Produces evidence by asking the TPM for a quote over the caller's
+/// nonce. This is exactly the code path `quotes_handler` used
+/// unconditionally before the driver framework existed.
+pub struct TpmEvidenceProvider {
+    /// The AK `main()` already provisioned at startup; quoting just
+    /// needs it loaded into a context, not re-created per request.
+    ak_handle: KeyHandle,
+}
+
+impl TpmEvidenceProvider {
+    /// Construct the driver around the agent's already-provisioned AK.
+    pub fn new(ak_handle: KeyHandle) -> Self {
+        TpmEvidenceProvider { ak_handle }
+    }
+}
+
+#[async_trait]
+impl EvidenceProvider for TpmEvidenceProvider {
+    fn evidence_type(&self) -> &'static str {
+        "tpm"
+    }
+
+    async fn get_evidence(&self, nonce: &[u8]) -> Result<Evidence> {
+        let mut ctx = tpm::get_tpm2_ctx()?;
+        let (quote, signature) =
+            tpm::quote(&mut ctx, self.ak_handle, nonce)?;
+        Ok(Evidence::Tpm { quote, signature })
+    }
+}